@@ -0,0 +1,416 @@
+//! Reads and writes the binary table a [`Module`] describes.
+//!
+//! A [`Module`] only describes the *shape* of a ROM table (entry count,
+//! entry length, and the components that make up an entry); this module
+//! is what actually turns the bytes at `root_offset + i * entry_length`
+//! into the text representation used throughout `.nmm` tooling, and back.
+
+use crate::{Component, ComponentKind, Error, Module, NumberFormat};
+
+/// Byte order used to read and write multi-byte [`ComponentKind::Number`]
+/// and [`ComponentKind::Dropbox`] fields.
+///
+/// FE/GBA data is little-endian, which is why it's the default, but the
+/// codec is written generically so other targets can opt into big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness
+{
+    #[default]
+    Little,
+    Big,
+}
+
+/// Decodes the component at `component_index` for entry `entry`, returning
+/// its text representation (the same form that would appear in a value
+/// file: a plain number, a sign-prefixed number, a hex string, or a
+/// dropbox label).
+pub fn get(module: &Module, buffer: &[u8], entry: u32, component_index: usize, endianness: Endianness) -> Result<String, Error>
+{
+    let component = module.components.get(component_index)
+        .ok_or(Error::InvalidComponentIndex { index: component_index })?;
+
+    let span = component_span(module, component, entry, buffer.len())?;
+    let bytes = &buffer[span.clone()];
+
+    let value = match &component.kind
+    {
+        ComponentKind::Text =>
+        {
+            let charset = module.charset.as_ref();
+            let mut result = String::new();
+
+            for &byte in bytes
+            {
+                match charset.and_then(|charset| charset.get(&byte))
+                {
+                    Some(&ch) => result.push(ch),
+                    None => break,
+                }
+            }
+
+            result
+        }
+
+        ComponentKind::HexArray => bytes_to_hex(bytes),
+
+        ComponentKind::Number(format) =>
+        {
+            validate_numeric_length(component)?;
+            format_number(read_number(bytes, endianness), component.length, format)
+        }
+
+        ComponentKind::Dropbox(format, entries) =>
+        {
+            validate_numeric_length(component)?;
+            let number = read_number(bytes, endianness);
+
+            match entries.iter().find(|(value, _)| *value == number)
+            {
+                Some((_, label)) => label.clone(),
+                None => format_number(number, component.length, format),
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+/// Re-encodes `value` (in the same text representation [`get`] returns)
+/// into `buffer` at the component's location.
+pub fn set(module: &Module, buffer: &mut [u8], entry: u32, component_index: usize, value: &str, endianness: Endianness) -> Result<(), Error>
+{
+    let component = module.components.get(component_index)
+        .ok_or(Error::InvalidComponentIndex { index: component_index })?;
+
+    let span = component_span(module, component, entry, buffer.len())?;
+
+    match &component.kind
+    {
+        ComponentKind::Text =>
+        {
+            let charset = module.charset.as_ref().ok_or(Error::InvalidCharset)?;
+
+            let reverse: std::collections::BTreeMap<char, u8> = charset.iter().map(|(&byte, &ch)| (ch, byte)).collect();
+
+            if value.chars().count() > span.len()
+            {
+                return Err(Error::ComponentOutOfBounds { entry, description: component.description.clone() });
+            }
+
+            let slice = &mut buffer[span.clone()];
+            slice.fill(0);
+
+            for (dst, ch) in slice.iter_mut().zip(value.chars())
+            {
+                *dst = *reverse.get(&ch).ok_or(Error::InvalidCharset)?;
+            }
+        }
+
+        ComponentKind::HexArray =>
+        {
+            let bytes = hex_to_bytes(value)?;
+            let slice = &mut buffer[span.clone()];
+
+            if bytes.len() != slice.len()
+            {
+                return Err(Error::ComponentOutOfBounds { entry, description: component.description.clone() });
+            }
+
+            slice.copy_from_slice(&bytes);
+        }
+
+        ComponentKind::Number(format) =>
+        {
+            validate_numeric_length(component)?;
+
+            let number = parse_number(value, format)?;
+            write_number(&mut buffer[span], number, component.length, endianness);
+        }
+
+        ComponentKind::Dropbox(format, entries) =>
+        {
+            validate_numeric_length(component)?;
+
+            let number = match entries.iter().find(|(_, label)| label == value)
+            {
+                Some((number, _)) => *number,
+                None => parse_number(value, format)?,
+            };
+
+            write_number(&mut buffer[span], number, component.length, endianness);
+        }
+    }
+
+    Ok(())
+}
+
+fn component_span(module: &Module, component: &Component, entry: u32, buffer_len: usize) -> Result<std::ops::Range<usize>, Error>
+{
+    let base = module.root_offset as usize + entry as usize * module.entry_length as usize;
+    let start = base + component.offset as usize;
+    let end = start + component.length as usize;
+
+    if end > buffer_len
+    {
+        return Err(Error::ComponentOutOfBounds { entry, description: component.description.clone() });
+    }
+
+    Ok(start..end)
+}
+
+/// Rejects the unvalidated field widths that would make [`read_number`],
+/// [`write_number`], or [`sign_extend`] panic: a `Number`/`Dropbox`
+/// component's `length` in bytes must fit a `u32`, and be at least wide
+/// enough for [`sign_extend`] to have a sign bit to test.
+fn validate_numeric_length(component: &Component) -> Result<(), Error>
+{
+    if (1..=4).contains(&component.length)
+    {
+        return Ok(());
+    }
+
+    Err(Error::InvalidComponentLength { description: component.description.clone(), length: component.length })
+}
+
+fn read_number(bytes: &[u8], endianness: Endianness) -> u32
+{
+    let mut result = 0u32;
+
+    match endianness
+    {
+        Endianness::Little =>
+        {
+            for (i, &byte) in bytes.iter().enumerate()
+            {
+                result |= (byte as u32) << (8 * i);
+            }
+        }
+
+        Endianness::Big =>
+        {
+            for &byte in bytes
+            {
+                result = (result << 8) | byte as u32;
+            }
+        }
+    }
+
+    result
+}
+
+fn write_number(bytes: &mut [u8], value: u32, length: u32, endianness: Endianness)
+{
+    match endianness
+    {
+        Endianness::Little =>
+        {
+            for (i, dst) in bytes.iter_mut().take(length as usize).enumerate()
+            {
+                *dst = (value >> (8 * i)) as u8;
+            }
+        }
+
+        Endianness::Big =>
+        {
+            let length = length as usize;
+
+            for (i, dst) in bytes.iter_mut().take(length).enumerate()
+            {
+                *dst = (value >> (8 * (length - 1 - i))) as u8;
+            }
+        }
+    }
+}
+
+fn format_number(value: u32, length: u32, format: &NumberFormat) -> String
+{
+    match format
+    {
+        NumberFormat::Hex => format!("0x{:X}", value),
+        NumberFormat::Dec => value.to_string(),
+        NumberFormat::DecSigned => sign_extend(value, length).to_string(),
+    }
+}
+
+fn parse_number(value: &str, format: &NumberFormat) -> Result<u32, Error>
+{
+    match format
+    {
+        // `crate::parse_int` is the `.nmm` grammar's own int literal syntax
+        // (leading-zero means octal, `0x` means hex) — fine for `Hex`,
+        // which is always typed with a `0x` prefix, but wrong for `Dec`:
+        // a user-typed decimal value like "0700" means 700, not octal 448.
+        NumberFormat::Hex => crate::parse_int(value),
+
+        NumberFormat::Dec => value.parse().map_err(|_| Error::InvalidNumberValue { value: value.to_string() }),
+
+        NumberFormat::DecSigned =>
+        {
+            let signed: i64 = value.parse().map_err(|_| Error::InvalidNumberValue { value: value.to_string() })?;
+            Ok(signed as u32)
+        }
+    }
+}
+
+fn sign_extend(value: u32, length: u32) -> i64
+{
+    let bits = length * 8;
+    let sign_bit = 1u32 << (bits - 1);
+
+    if value & sign_bit != 0
+    {
+        (value as i64) - (1i64 << bits)
+    }
+    else
+    {
+        value as i64
+    }
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, Error>
+{
+    if !hex.len().is_multiple_of(2)
+    {
+        return Err(Error::InvalidHexValue { value: hex.to_string() });
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::InvalidHexValue { value: hex.to_string() }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn module_with(length: u32, kind: ComponentKind) -> Module
+    {
+        let mut module = Module
+        {
+            root_offset: 0,
+            entry_count: 1,
+            entry_length: length,
+            ..Module::default()
+        };
+
+        module.components.push(Component
+        {
+            description: "Field".into(),
+            offset: 0,
+            length,
+            kind,
+            dropbox_path: None,
+            dropbox_hash: None,
+        });
+
+        module
+    }
+
+    #[test]
+    fn number_round_trips_through_get_and_set()
+    {
+        let module = module_with(2, ComponentKind::Number(NumberFormat::Dec));
+        let mut buffer = vec![0u8; 2];
+
+        set(&module, &mut buffer, 0, 0, "513", Endianness::Little).unwrap();
+        assert_eq!(buffer, vec![0x01, 0x02]);
+        assert_eq!(get(&module, &buffer, 0, 0, Endianness::Little).unwrap(), "513");
+    }
+
+    #[test]
+    fn signed_number_sign_extends_negative_values()
+    {
+        let module = module_with(1, ComponentKind::Number(NumberFormat::DecSigned));
+        let buffer = vec![0xFFu8];
+
+        assert_eq!(get(&module, &buffer, 0, 0, Endianness::Little).unwrap(), "-1");
+    }
+
+    #[test]
+    fn dropbox_prefers_label_over_raw_number()
+    {
+        let module = module_with(1, ComponentKind::Dropbox(NumberFormat::Dec, vec![(7, "Lucky".into())]));
+        let buffer = vec![7u8];
+
+        assert_eq!(get(&module, &buffer, 0, 0, Endianness::Little).unwrap(), "Lucky");
+
+        let mut buffer = vec![0u8];
+        set(&module, &mut buffer, 0, 0, "Lucky", Endianness::Little).unwrap();
+        assert_eq!(buffer, vec![7]);
+    }
+
+    #[test]
+    fn hex_array_round_trips()
+    {
+        let module = module_with(2, ComponentKind::HexArray);
+        let mut buffer = vec![0u8; 2];
+
+        set(&module, &mut buffer, 0, 0, "0AFF", Endianness::Little).unwrap();
+        assert_eq!(buffer, vec![0x0A, 0xFF]);
+        assert_eq!(get(&module, &buffer, 0, 0, Endianness::Little).unwrap(), "0AFF");
+    }
+
+    #[test]
+    fn invalid_numeric_length_is_a_structured_error_not_a_panic()
+    {
+        let too_wide = module_with(8, ComponentKind::Number(NumberFormat::Dec));
+        let buffer = vec![0u8; 8];
+        assert!(matches!(get(&too_wide, &buffer, 0, 0, Endianness::Little), Err(Error::InvalidComponentLength { .. })));
+
+        let zero_width = module_with(0, ComponentKind::Number(NumberFormat::DecSigned));
+        let buffer = vec![0u8; 0];
+        assert!(matches!(get(&zero_width, &buffer, 0, 0, Endianness::Little), Err(Error::InvalidComponentLength { .. })));
+    }
+
+    #[test]
+    fn dec_format_parses_a_leading_zero_as_decimal_not_octal()
+    {
+        let module = module_with(2, ComponentKind::Number(NumberFormat::Dec));
+        let mut buffer = vec![0u8; 2];
+
+        set(&module, &mut buffer, 0, 0, "0700", Endianness::Little).unwrap();
+        assert_eq!(get(&module, &buffer, 0, 0, Endianness::Little).unwrap(), "700");
+    }
+
+    #[test]
+    fn invalid_signed_number_value_is_not_reported_as_an_invalid_component_kind()
+    {
+        let module = module_with(1, ComponentKind::Number(NumberFormat::DecSigned));
+        let mut buffer = vec![0u8; 1];
+
+        assert!(matches!(
+            set(&module, &mut buffer, 0, 0, "not a number", Endianness::Little),
+            Err(Error::InvalidNumberValue { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_hex_value_is_not_reported_as_an_invalid_component_kind()
+    {
+        let module = module_with(1, ComponentKind::HexArray);
+        let mut buffer = vec![0u8; 1];
+
+        assert!(matches!(set(&module, &mut buffer, 0, 0, "ZZ", Endianness::Little), Err(Error::InvalidHexValue { .. })));
+        assert!(matches!(set(&module, &mut buffer, 0, 0, "0", Endianness::Little), Err(Error::InvalidHexValue { .. })));
+    }
+
+    #[test]
+    fn text_value_longer_than_the_component_is_rejected_instead_of_truncated()
+    {
+        let mut module = module_with(2, ComponentKind::Text);
+        module.charset = Some(std::collections::BTreeMap::from([(0x41, 'A'), (0x42, 'B'), (0x43, 'C')]));
+        let mut buffer = vec![0u8; 2];
+
+        assert!(matches!(
+            set(&module, &mut buffer, 0, 0, "ABC", Endianness::Little),
+            Err(Error::ComponentOutOfBounds { .. })
+        ));
+    }
+}
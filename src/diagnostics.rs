@@ -0,0 +1,101 @@
+//! Renders an [`Error::Located`] as an annotated source snippet, in the
+//! style of `codespan-reporting`: the offending line, followed by a caret
+//! underlining the exact token that failed to parse.
+//!
+//! [`Error`]'s `Display` impl stays terse (`At file:line:col: message`) for
+//! logs; [`Error::report`] is for surfacing the error to a human editing
+//! the `.nmm` file.
+
+use crate::Error;
+
+pub(crate) fn render(error: &Error) -> String
+{
+    match error
+    {
+        Error::Located { filename, line, column, length, line_text, source } =>
+        {
+            let gutter = format!("{}", line);
+            let pad = " ".repeat(gutter.len());
+
+            format!(
+                "error: {message}\n  --> {filename}:{line}:{column}\n{pad} |\n{gutter} | {line_text}\n{pad} | {caret_pad}{caret}\n",
+                message = innermost_message(source),
+                filename = filename.display(),
+                line = line,
+                column = column + 1,
+                pad = pad,
+                gutter = gutter,
+                line_text = line_text,
+                caret_pad = " ".repeat(*column),
+                caret = "^".repeat((*length).max(1)),
+            )
+        }
+
+        other => format!("error: {}\n", other),
+    }
+}
+
+fn innermost_message(error: &Error) -> String
+{
+    match error
+    {
+        Error::Located { source, .. } => innermost_message(source),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn renders_a_located_error_with_a_caret_under_the_token()
+    {
+        let error = Error::Located
+        {
+            filename: PathBuf::from("dat/Charset.txt"),
+            line: 3,
+            column: 3,
+            length: 2,
+            line_text: "ZZ=A".to_string(),
+            source: Box::new(Error::InvalidCharset),
+        };
+
+        assert_eq!(
+            render(&error),
+            "error: Malformed charset file\n  --> dat/Charset.txt:3:4\n  |\n3 | ZZ=A\n  |    ^^\n"
+        );
+    }
+
+    #[test]
+    fn unwraps_nested_located_errors_to_the_innermost_message()
+    {
+        let error = Error::Located
+        {
+            filename: PathBuf::from("dat/main.nmm"),
+            line: 1,
+            column: 0,
+            length: 1,
+            line_text: "%include missing.nmm".to_string(),
+            source: Box::new(Error::Located
+            {
+                filename: PathBuf::from("dat/missing.nmm"),
+                line: 5,
+                column: 0,
+                length: 3,
+                line_text: "xyz".to_string(),
+                source: Box::new(Error::InvalidModuleVersion),
+            }),
+        };
+
+        assert!(render(&error).starts_with("error: Invalid module version"));
+    }
+
+    #[test]
+    fn falls_back_to_the_terse_display_form_without_a_location()
+    {
+        assert_eq!(render(&Error::InvalidModuleVersion), "error: Invalid module version (it can only be \"1\")\n");
+    }
+}
@@ -2,18 +2,29 @@
 use std::collections::BTreeMap;
 use std::io::{self, BufRead};
 use std::path::{Path, PathBuf};
-use std::fs::File;
 
 use thiserror::Error;
 
+pub mod codec;
+mod diagnostics;
+pub mod loader;
+pub mod reader;
+pub mod writer;
+
+use loader::{FileKind, FsLoader, ResourceLoader};
+use reader::{ModuleItem, ModuleReader};
+
 #[derive(Error, Debug)]
 pub enum Error
 {
-    #[error("At {filename}:{line}: {source}")]
+    #[error("At {filename}:{line}:{column}: {source}", column = column + 1)]
     Located
     {
         filename: PathBuf,
         line: usize,
+        column: usize,
+        length: usize,
+        line_text: String,
         source: Box<Error>,
     },
 
@@ -29,6 +40,59 @@ pub enum Error
     #[error("Too many component entries")]
     TooManyComponentEntries,
 
+    #[error("%include cycle detected at {path}", path = path.display())]
+    IncludeCycle
+    {
+        path: PathBuf,
+    },
+
+    #[error("%include depth limit exceeded")]
+    IncludeDepthExceeded,
+
+    #[error("Refusing to overwrite {path} (it changed on disk since the module was loaded)", path = path.display())]
+    AuxiliaryFileModified
+    {
+        path: PathBuf,
+    },
+
+    #[error("{description} has data to write but no path was set for it")]
+    MissingAuxiliaryPath
+    {
+        description: String,
+    },
+
+    #[error("Component \"{description}\" has an invalid length ({length}) for a numeric field (must be 1-4 bytes)")]
+    InvalidComponentLength
+    {
+        description: String,
+        length: u32,
+    },
+
+    #[error("\"{value}\" is not a valid numeric value")]
+    InvalidNumberValue
+    {
+        value: String,
+    },
+
+    #[error("\"{value}\" is not a valid hex string")]
+    InvalidHexValue
+    {
+        value: String,
+    },
+
+    #[error("Component \"{description}\" (entry {entry}) does not fit in the buffer")]
+    ComponentOutOfBounds
+    {
+        entry: u32,
+        description: String,
+    },
+
+    #[error("Component index {index} out of range")]
+    InvalidComponentIndex
+    {
+        index: usize,
+    },
+
     #[error("Unexpected end of module file")]
     UnexpectedEof,
 
@@ -47,6 +111,17 @@ pub enum Error
     },
 }
 
+impl Error
+{
+    /// Renders a [`Error::Located`] as an annotated source snippet (the
+    /// offending line with a caret under the bad token). Errors without
+    /// location information just render their terse `Display` form.
+    pub fn report(&self) -> String
+    {
+        diagnostics::render(self)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum NumberFormat
 {
@@ -71,6 +146,15 @@ pub struct Component
     pub offset: u32,
     pub length: u32,
     pub kind: ComponentKind,
+
+    /// Path of the dropbox entries file this component was loaded from
+    /// (`None` for a `NULL` reference, or for non-`Dropbox` kinds).
+    pub dropbox_path: Option<PathBuf>,
+
+    /// Hash of `kind`'s dropbox entries as they were when loaded, used by
+    /// [`writer`](crate::writer) to tell whether the file changed on disk
+    /// since then.
+    pub dropbox_hash: Option<u64>,
 }
 
 #[derive(Default, Debug, PartialEq)]
@@ -83,152 +167,93 @@ pub struct Module
     pub entry_names: Option<Vec<String>>,
     pub charset: Option<BTreeMap<u8, char>>,
     pub components: Vec<Component>,
+
+    /// Path of the entry-names file this module was loaded from (`None`
+    /// for a `NULL` reference).
+    pub entry_names_path: Option<PathBuf>,
+
+    /// Hash of `entry_names` as it was when loaded, used by
+    /// [`writer`](crate::writer) to tell whether the file changed on disk
+    /// since then.
+    pub entry_names_hash: Option<u64>,
+
+    /// Path of the charset file this module was loaded from (`None` for a
+    /// `NULL` reference).
+    pub charset_path: Option<PathBuf>,
+
+    /// Hash of `charset` as it was when loaded, used by
+    /// [`writer`](crate::writer) to tell whether the file changed on disk
+    /// since then.
+    pub charset_hash: Option<u64>,
 }
 
 pub fn from_file<P>(filename: P) -> Result<Module, Error>
     where P: AsRef<Path>
 {
-    enum ReadState
-    {
-        ReadVersion,
-        ReadDescription,
-        ReadRootOffset,
-        ReadEntryCount,
-        ReadEntryLength,
-        ReadEntryNames,
-        ReadCharset,
-        ReadNextComponentDescription,
-        ReadComponentOffset,
-        ReadComponentLength,
-        ReadComponentKind,
-        ReadComponentDropboxEntriesAndEnd,
-    }
+    from_loader(&FsLoader, filename)
+}
 
+/// Drains a [`ModuleReader`] into a fully materialized [`Module`],
+/// resolving every entry-names, charset, and dropbox file it references
+/// along the way. Callers that don't need the whole module (e.g. a
+/// search that only cares about a few components) can use
+/// [`ModuleReader`] directly instead and skip what they don't need.
+pub fn from_loader<L, P>(loader: &L, filename: P) -> Result<Module, Error>
+    where L: ResourceLoader + ?Sized, P: AsRef<Path>
+{
     let mut result = Module::default();
-    let mut read_state = ReadState::ReadVersion;
-
-    let parent_dir = filename.as_ref().parent().unwrap_or_else(|| Path::new(""));
+    let reader = ModuleReader::new(loader, filename)?;
 
-    let mut current_component_desc: Option<String> = None;
-    let mut current_component_offset = 0u32;
-    let mut current_component_length = 0u32;
-    let mut current_component_kind_str: Option<String> = None;
-
-    for (i, line) in read_lines(&filename)?.enumerate()
+    for item in reader
     {
-        let line_str = line?;
-        let line = line_str.trim();
-
-        // skip empty lines
-        if line.is_empty() { continue }
-
-        // skip comments
-        if line.starts_with('#') { continue }
-
-        let located_err = |err: Error| Error::Located { filename: filename.as_ref().into(), line: i + 1, source: Box::new(err) };
-
-        match read_state
+        match item?
         {
-            ReadState::ReadVersion =>
+            ModuleItem::Header { description, root_offset, entry_count, entry_length } =>
             {
-                match line
-                {
-                    "1" => {},
-                    _ => { return Err(Error::InvalidModuleVersion) }
-                }
-
-                read_state = ReadState::ReadDescription;
+                result.description = description;
+                result.root_offset = root_offset;
+                result.entry_count = entry_count;
+                result.entry_length = entry_length;
             }
 
-            ReadState::ReadDescription =>
+            ModuleItem::EntryNames(path) =>
             {
-                result.description = line_str;
-                read_state = ReadState::ReadRootOffset;
+                result.entry_names = read_module_entries(loader, path.clone())?;
+                result.entry_names_path = path;
+                result.entry_names_hash = result.entry_names.as_ref().map(hash_value);
             }
 
-            ReadState::ReadRootOffset =>
+            ModuleItem::Charset(Some(path)) =>
             {
-                result.root_offset = parse_int(line).map_err(located_err)?;
-                read_state = ReadState::ReadEntryCount;
-            }
+                let charset = read_charset(loader, &path)?;
 
-            ReadState::ReadEntryCount =>
-            {
-                result.entry_count = parse_int(line).map_err(located_err)?;
-                read_state = ReadState::ReadEntryLength;
+                result.charset_hash = Some(hash_value(&charset));
+                result.charset_path = Some(path);
+                result.charset = Some(charset);
             }
 
-            ReadState::ReadEntryLength =>
-            {
-                result.entry_length = parse_int(line).map_err(located_err)?;
-                read_state = ReadState::ReadEntryNames;
-            }
+            ModuleItem::Charset(None) => {}
 
-            ReadState::ReadEntryNames =>
+            ModuleItem::Component { description, offset, length, kind_str, dropbox_path } =>
             {
-                result.entry_names = read_module_entries(get_full_filename(parent_dir, line))?;
-                read_state = ReadState::ReadCharset;
+                result.components.push(build_component(loader, description, offset, length, &kind_str, dropbox_path)?);
             }
 
-            ReadState::ReadCharset =>
+            ModuleItem::Unset(description) =>
             {
-                match get_full_filename(parent_dir, line)
-                {
-                    Some(filename) => { result.charset = Some(read_charset(filename)?); }
-                    None => {}
-                }
-
-                read_state = ReadState::ReadNextComponentDescription;
-            }
-
-            ReadState::ReadNextComponentDescription =>
-            {
-                current_component_desc = Some(line_str);
-                read_state = ReadState::ReadComponentOffset;
-            }
-
-            ReadState::ReadComponentOffset =>
-            {
-                current_component_offset = parse_int(line).map_err(located_err)?;
-                read_state = ReadState::ReadComponentLength;
-            }
-
-            ReadState::ReadComponentLength =>
-            {
-                current_component_length = parse_int(line).map_err(located_err)?;
-                read_state = ReadState::ReadComponentKind;
-            }
-
-            ReadState::ReadComponentKind =>
-            {
-                current_component_kind_str = Some(line_str);
-                read_state = ReadState::ReadComponentDropboxEntriesAndEnd;
-            }
-
-            ReadState::ReadComponentDropboxEntriesAndEnd =>
-            {
-                result.components.push(build_component(
-                    current_component_desc.take().unwrap(),
-                    current_component_offset,
-                    current_component_length,
-                    &current_component_kind_str.take().unwrap(),
-                    get_full_filename(parent_dir, line))?);
-
-                read_state = ReadState::ReadNextComponentDescription;
+                result.components.retain(|component| component.description != description);
             }
         }
     }
 
-    match read_state
-    {
-        ReadState::ReadNextComponentDescription => Ok(result),
-        _ => Err(Error::UnexpectedEof),
-    }
+    Ok(result)
 }
 
-fn build_component<P>(description: String, offset: u32, length: u32, kind_str: &str, dropbox_entry_file: Option<P>) -> Result<Component, Error>
-    where P: AsRef<Path>
+fn build_component<L>(loader: &L, description: String, offset: u32, length: u32, kind_str: &str, dropbox_entry_file: Option<PathBuf>) -> Result<Component, Error>
+    where L: ResourceLoader + ?Sized
 {
+    let is_dropbox = matches!(kind_str.trim(), "NDHU" | "NDDU");
+
     let kind = match kind_str.trim()
     {
         "TEXT" => ComponentKind::Text,
@@ -236,39 +261,57 @@ fn build_component<P>(description: String, offset: u32, length: u32, kind_str: &
         "NEHU" => ComponentKind::Number(NumberFormat::Hex),
         "NEDU" => ComponentKind::Number(NumberFormat::Dec),
         "NEDS" => ComponentKind::Number(NumberFormat::DecSigned),
-        "NDHU" => ComponentKind::Dropbox(NumberFormat::Hex, read_component_dropbox_entries(dropbox_entry_file)?),
-        "NDDU" => ComponentKind::Dropbox(NumberFormat::Dec, read_component_dropbox_entries(dropbox_entry_file)?),
+        "NDHU" => ComponentKind::Dropbox(NumberFormat::Hex, read_component_dropbox_entries(loader, dropbox_entry_file.clone())?),
+        "NDDU" => ComponentKind::Dropbox(NumberFormat::Dec, read_component_dropbox_entries(loader, dropbox_entry_file.clone())?),
 
         _ => return Err(Error::InvalidComponentKind),
     };
 
+    let dropbox_hash = match &kind
+    {
+        ComponentKind::Dropbox(_, entries) => Some(hash_value(entries)),
+        _ => None,
+    };
+
     Ok(Component
     {
         description: description,
         offset: offset,
         length: length,
         kind: kind,
+        dropbox_path: if is_dropbox { dropbox_entry_file } else { None },
+        dropbox_hash,
     })
 }
 
-fn get_full_filename<P>(parent_dir: P, filename: &str) -> Option<PathBuf>
+pub(crate) fn get_full_filename<P>(parent_dir: P, filename: &str) -> Option<PathBuf>
     where P: AsRef<Path>
 {
     match filename
     {
         "NULL" => None,
-        _ => Some(parent_dir.as_ref().join(filename))
+
+        _ =>
+        {
+            let joined = parent_dir.as_ref().join(filename);
+
+            // Canonicalize so paths stay comparable (e.g. across a
+            // to_file/from_file round trip) regardless of which directory
+            // they were originally resolved relative to. Falls back to the
+            // plain joined path for loaders that aren't filesystem-backed.
+            Some(std::fs::canonicalize(&joined).unwrap_or(joined))
+        }
     }
 }
 
-fn read_module_entries<P>(filename: Option<P>) -> Result<Option<Vec<String>>, Error>
-    where P: AsRef<Path>
+pub(crate) fn read_module_entries<L, P>(loader: &L, filename: Option<P>) -> Result<Option<Vec<String>>, Error>
+    where L: ResourceLoader + ?Sized, P: AsRef<Path>
 {
     if let Some(filename) = filename
     {
         let mut result: Vec<String> = Vec::new();
 
-        for line in read_lines(filename)?
+        for line in read_lines(loader, FileKind::EntryNames, filename.as_ref())?
         {
             result.push(line?);
         }
@@ -279,26 +322,34 @@ fn read_module_entries<P>(filename: Option<P>) -> Result<Option<Vec<String>>, Er
     Ok(None)
 }
 
-fn read_charset<P>(filename: P) -> Result<BTreeMap<u8, char>, Error>
-    where P: AsRef<Path>
+pub(crate) fn read_charset<L, P>(loader: &L, filename: P) -> Result<BTreeMap<u8, char>, Error>
+    where L: ResourceLoader + ?Sized, P: AsRef<Path>
 {
     let mut result: BTreeMap<u8, char> = BTreeMap::new();
 
-    for (i, line) in read_lines(&filename)?.enumerate()
+    for (i, line) in read_lines(loader, FileKind::Charset, filename.as_ref())?.enumerate()
     {
         let line = line?;
         let line = line.trim();
 
-        let located_err = |err: Error| Error::Located { filename: filename.as_ref().into(), line: i + 1, source: Box::new(err) };
+        let located_err = |column: usize, length: usize, err: Error| Error::Located
+        {
+            filename: filename.as_ref().into(),
+            line: i + 1,
+            column,
+            length,
+            line_text: line.to_string(),
+            source: Box::new(err),
+        };
 
         let split: Vec<&str> = line.split('=').map(|s| s.trim()).collect();
 
         if split.len() != 2
         {
-            return Err(located_err(Error::InvalidCharset));
+            return Err(located_err(0, line.len(), Error::InvalidCharset));
         }
 
-        let number = u32::from_str_radix(split[0], 16).map_err(|err| located_err(err.into()))?;
+        let number = u32::from_str_radix(split[0], 16).map_err(|err| located_err(0, split[0].len(), err.into()))?;
         let character = split[1].chars().next().unwrap_or('\x00');
 
         result.insert(number as u8, character);
@@ -307,8 +358,8 @@ fn read_charset<P>(filename: P) -> Result<BTreeMap<u8, char>, Error>
     Ok(result)
 }
 
-fn read_component_dropbox_entries<P>(filename: Option<P>) -> Result<Vec<(u32, String)>, Error>
-    where P: AsRef<Path>
+pub(crate) fn read_component_dropbox_entries<L, P>(loader: &L, filename: Option<P>) -> Result<Vec<(u32, String)>, Error>
+    where L: ResourceLoader + ?Sized, P: AsRef<Path>
 {
     if let Some(filename) = filename
     {
@@ -317,16 +368,24 @@ fn read_component_dropbox_entries<P>(filename: Option<P>) -> Result<Vec<(u32, St
         let mut is_first_line = true;
         let mut entries_left = 0;
 
-        for (i, line) in read_lines(&filename)?.enumerate()
+        for (i, line) in read_lines(loader, FileKind::Dropbox, filename.as_ref())?.enumerate()
         {
             let line = line?;
             let line = line.trim();
 
-            let located_err = |err: Error| Error::Located { filename: filename.as_ref().into(), line: i + 1, source: Box::new(err) };
+            let located_err = |column: usize, length: usize, err: Error| Error::Located
+            {
+                filename: filename.as_ref().into(),
+                line: i + 1,
+                column,
+                length,
+                line_text: line.to_string(),
+                source: Box::new(err),
+            };
 
             if is_first_line
             {
-                entries_left = parse_int(line).map_err(located_err)?;
+                entries_left = parse_int(line).map_err(|err| located_err(0, line.len(), err))?;
                 is_first_line = false;
             }
             else
@@ -338,7 +397,7 @@ fn read_component_dropbox_entries<P>(filename: Option<P>) -> Result<Vec<(u32, St
 
                 let split: Vec<&str> = line.splitn(2, ' ').collect();
 
-                let num = parse_int(split[0]).map_err(located_err)?;
+                let num = parse_int(split[0]).map_err(|err| located_err(0, split[0].len(), err))?;
                 result.push((num, split[1].into()));
 
                 entries_left -= 1;
@@ -351,14 +410,24 @@ fn read_component_dropbox_entries<P>(filename: Option<P>) -> Result<Vec<(u32, St
     Ok(Vec::new())
 }
 
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-    where P: AsRef<Path>
+fn read_lines<L>(loader: &L, kind: FileKind, filename: &Path) -> io::Result<io::Lines<Box<dyn BufRead>>>
+    where L: ResourceLoader + ?Sized
+{
+    Ok(loader.open(kind, filename)?.lines())
+}
+
+/// Fingerprints a loaded value so the [`writer`](crate::writer) can later
+/// tell whether the on-disk file it came from changed in the meantime.
+pub(crate) fn hash_value<T: std::hash::Hash>(value: &T) -> u64
 {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    use std::hash::Hasher;
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
 }
 
-fn parse_int(input: &str) -> Result<u32, Error>
+pub(crate) fn parse_int(input: &str) -> Result<u32, Error>
 {
     if input.starts_with('0')
     {
@@ -394,4 +463,45 @@ mod tests
     {
         let _ = from_file("dat/SpellAssoc.nmm").unwrap();
     }
+
+    #[test]
+    fn round_trip()
+    {
+        let dir = std::fs::read_dir("dat").unwrap();
+
+        for entry in dir
+        {
+            let entry_path = entry.unwrap().path();
+
+            if entry_path.extension().and_then(|ext| ext.to_str()) != Some("nmm")
+            {
+                continue;
+            }
+
+            // Work in an isolated copy of the whole fixture directory so the
+            // round-tripped output's auxiliary files land on top of the
+            // originals (same name, same directory) rather than a different
+            // one, keeping the reloaded module's paths comparable to the
+            // original's.
+            let work_dir = std::env::temp_dir().join(format!("nightmare-round-trip-{}", entry_path.file_stem().unwrap().to_string_lossy()));
+            let _ = std::fs::remove_dir_all(&work_dir);
+            std::fs::create_dir_all(&work_dir).unwrap();
+
+            for file in std::fs::read_dir("dat").unwrap()
+            {
+                let file = file.unwrap().path();
+                std::fs::copy(&file, work_dir.join(file.file_name().unwrap())).unwrap();
+            }
+
+            let path = work_dir.join(entry_path.file_name().unwrap());
+            let module = from_file(&path).unwrap();
+
+            let out_path = work_dir.join("out.nmm");
+            crate::writer::to_file(&module, &out_path).unwrap();
+
+            let reloaded = from_file(&out_path).unwrap();
+
+            assert_eq!(module, reloaded, "round-trip mismatch for {}", path.display());
+        }
+    }
 }
@@ -0,0 +1,95 @@
+//! Decouples `.nmm` parsing from `std::fs`.
+//!
+//! [`from_file`](crate::from_file) (and the helpers it calls) used
+//! to open every file it needed with `File::open` directly, which meant a
+//! module could only ever be loaded from the local filesystem. A
+//! [`ResourceLoader`] is the seam that lets a caller supply bytes from
+//! somewhere else instead — an archive, an in-memory map, a test fixture —
+//! while still letting it tell files apart by their [`FileKind`].
+
+use std::io::{self, BufRead, BufReader};
+use std::fs::File;
+use std::path::Path;
+
+/// What a file being opened is used for, so a [`ResourceLoader`] can apply
+/// a different policy per kind (e.g. case-insensitive lookup only for
+/// charset files).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind
+{
+    Module,
+    EntryNames,
+    Charset,
+    Dropbox,
+}
+
+/// Resolves a `(kind, path)` pair to a readable stream.
+///
+/// `path` is already resolved relative to the referencing file, exactly as
+/// `std::fs::File::open` would expect it.
+pub trait ResourceLoader
+{
+    fn open(&self, kind: FileKind, path: &Path) -> io::Result<Box<dyn BufRead>>;
+}
+
+/// The default loader, used by [`crate::from_file`]: opens files directly
+/// off the local filesystem, ignoring `kind`.
+pub struct FsLoader;
+
+impl ResourceLoader for FsLoader
+{
+    fn open(&self, _kind: FileKind, path: &Path) -> io::Result<Box<dyn BufRead>>
+    {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    #[test]
+    fn fs_loader_opens_an_existing_file()
+    {
+        let mut lines = FsLoader.open(FileKind::Module, Path::new("dat/SpellAssoc.nmm")).unwrap().lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "1");
+    }
+
+    #[test]
+    fn fs_loader_reports_a_missing_file_as_an_io_error()
+    {
+        assert!(FsLoader.open(FileKind::Module, Path::new("dat/DoesNotExist.nmm")).is_err());
+    }
+
+    /// A loader backed entirely by an in-memory map, demonstrating the
+    /// point of [`ResourceLoader`]: a module can be parsed without ever
+    /// touching the filesystem.
+    struct MapLoader(BTreeMap<PathBuf, String>);
+
+    impl ResourceLoader for MapLoader
+    {
+        fn open(&self, _kind: FileKind, path: &Path) -> io::Result<Box<dyn BufRead>>
+        {
+            let content = self.0.get(path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(Box::new(Cursor::new(content.clone().into_bytes())))
+        }
+    }
+
+    #[test]
+    fn a_non_filesystem_loader_can_parse_a_module()
+    {
+        let loader = MapLoader(BTreeMap::from([(
+            PathBuf::from("mem.nmm"),
+            "1\nIn Memory\n0\n1\n1\nNULL\nNULL\nOnly\n0\n1\nHEXA\nNULL\n".to_string(),
+        )]));
+
+        let module = crate::from_loader(&loader, "mem.nmm").unwrap();
+
+        assert_eq!(module.description, "In Memory");
+        assert_eq!(module.components.len(), 1);
+    }
+}
@@ -0,0 +1,508 @@
+//! Streaming, pull-based alternative to [`crate::from_loader`]'s eager
+//! parse: a [`ModuleReader`] yields the pieces of a `.nmm` file one at a
+//! time as they're read off the line stream, instead of materializing a
+//! full [`Module`](crate::Module) up front.
+//!
+//! This mirrors the move from eager `str` splitting to pull iterators:
+//! a caller that only cares about, say, `TEXT` components can stop
+//! iterating as soon as it's seen enough, without ever resolving the
+//! entry-names, charset, or dropbox files referenced along the way.
+//! [`crate::from_loader`] is itself just a consumer that drains a
+//! [`ModuleReader`] into a [`Module`](crate::Module).
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use crate::loader::{FileKind, ResourceLoader};
+use crate::{get_full_filename, Error};
+
+/// How many module files (the root file plus its nested `%include`s) may
+/// be open at once, as a backstop against runaway (non-cyclic) include
+/// chains.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// One piece of a `.nmm` file, in the order it appears on disk.
+///
+/// `EntryNames`, `Charset`, and a `Component`'s dropbox file are handed
+/// back as unresolved paths rather than parsed content — resolving them
+/// is a separate, explicit step (see [`crate::read_module_entries`],
+/// [`crate::read_charset`], [`crate::read_component_dropbox_entries`]),
+/// so a caller that doesn't need them never pays for reading them.
+#[derive(Debug, PartialEq)]
+pub enum ModuleItem
+{
+    Header
+    {
+        description: String,
+        root_offset: u32,
+        entry_count: u32,
+        entry_length: u32,
+    },
+
+    EntryNames(Option<PathBuf>),
+
+    Charset(Option<PathBuf>),
+
+    Component
+    {
+        description: String,
+        offset: u32,
+        length: u32,
+        kind_str: String,
+        dropbox_path: Option<PathBuf>,
+    },
+
+    /// A `%unset <description>` directive: the component previously
+    /// yielded under this description no longer applies. `ModuleReader`
+    /// doesn't retract anything itself (the item is already out the
+    /// door) — it's up to the consumer to drop it from whatever it's
+    /// been accumulating.
+    Unset(String),
+}
+
+enum State
+{
+    Version,
+    Description,
+    RootOffset,
+    EntryCount,
+    EntryLength,
+    EntryNames,
+    Charset,
+    ComponentDescription,
+    ComponentOffset,
+    ComponentLength,
+    ComponentKind,
+    ComponentDropboxAndEnd,
+}
+
+struct IncludeFrame
+{
+    path: PathBuf,
+    lines: std::io::Lines<Box<dyn std::io::BufRead>>,
+    line_no: usize,
+}
+
+/// Pulls [`ModuleItem`]s one at a time out of a `.nmm` file (and whatever
+/// it `%include`s), in the order they appear on disk.
+pub struct ModuleReader<'l, L: ?Sized>
+{
+    loader: &'l L,
+    frames: Vec<IncludeFrame>,
+    state: State,
+
+    description: String,
+    root_offset: u32,
+    entry_count: u32,
+
+    component_description: Option<String>,
+    component_offset: u32,
+    component_length: u32,
+    component_kind_str: Option<String>,
+}
+
+impl<'l, L> ModuleReader<'l, L>
+    where L: ResourceLoader + ?Sized
+{
+    pub fn new<P>(loader: &'l L, filename: P) -> Result<Self, Error>
+        where P: AsRef<Path>
+    {
+        let mut reader = ModuleReader
+        {
+            loader,
+            frames: Vec::new(),
+            state: State::Version,
+
+            description: String::new(),
+            root_offset: 0,
+            entry_count: 0,
+
+            component_description: None,
+            component_offset: 0,
+            component_length: 0,
+            component_kind_str: None,
+        };
+
+        reader.push_include(filename.as_ref().to_path_buf())?;
+
+        Ok(reader)
+    }
+
+    fn push_include(&mut self, path: PathBuf) -> Result<(), Error>
+    {
+        // Canonicalize before comparing, the same way `get_full_filename`
+        // does, so two `%include`s of the same file spelled differently
+        // (e.g. via `..`) are recognized as a cycle instead of re-entering
+        // the same file's line stream as if it were new content.
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+
+        if self.frames.iter().any(|frame| frame.path == path)
+        {
+            return Err(Error::IncludeCycle { path });
+        }
+
+        if self.frames.len() >= MAX_INCLUDE_DEPTH
+        {
+            return Err(Error::IncludeDepthExceeded);
+        }
+
+        let lines = self.loader.open(FileKind::Module, &path)?.lines();
+        self.frames.push(IncludeFrame { path, lines, line_no: 0 });
+
+        Ok(())
+    }
+
+    /// Returns the next preprocessed `(originating file, 0-based line
+    /// number, content)` triple, splicing in `%include`s and applying
+    /// `%unset` is left to the caller (see [`Self::next`]). Comments and
+    /// blank lines are skipped here; popping exhausted includes happens
+    /// transparently.
+    fn next_raw_line(&mut self) -> Result<Option<(PathBuf, usize, String)>, Error>
+    {
+        loop
+        {
+            let frame = match self.frames.last_mut()
+            {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            let line = match frame.lines.next()
+            {
+                Some(line) =>
+                {
+                    let line_no = frame.line_no;
+                    frame.line_no += 1;
+                    (frame.path.clone(), line_no, line?)
+                }
+
+                None =>
+                {
+                    self.frames.pop();
+                    continue;
+                }
+            };
+
+            let trimmed = line.2.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with('#')
+            {
+                continue;
+            }
+
+            if let Some(path) = trimmed.strip_prefix("%include ")
+            {
+                let parent_dir = line.0.parent().unwrap_or_else(|| Path::new(""));
+                self.push_include(parent_dir.join(path.trim()))?;
+                continue;
+            }
+
+            return Ok(Some(line));
+        }
+    }
+}
+
+impl<'l, L> Iterator for ModuleReader<'l, L>
+    where L: ResourceLoader + ?Sized
+{
+    type Item = Result<ModuleItem, Error>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        loop
+        {
+            let (cur_path, i, line_str) = match self.next_raw_line()
+            {
+                Ok(Some(line)) => line,
+                Ok(None) =>
+                {
+                    return match self.state
+                    {
+                        State::ComponentDescription => None,
+                        _ => Some(Err(Error::UnexpectedEof)),
+                    };
+                }
+                Err(err) => return Some(Err(err)),
+            };
+
+            let line = line_str.trim();
+            let parent_dir = cur_path.parent().unwrap_or_else(|| Path::new(""));
+
+            if let Some(description) = line.strip_prefix("%unset ")
+            {
+                return Some(Ok(ModuleItem::Unset(description.trim().to_string())));
+            }
+
+            let located_err = |column: usize, length: usize, err: Error| Error::Located
+            {
+                filename: cur_path.clone(),
+                line: i + 1,
+                column,
+                length,
+                line_text: line.to_string(),
+                source: Box::new(err),
+            };
+
+            match self.state
+            {
+                State::Version =>
+                {
+                    if line != "1"
+                    {
+                        return Some(Err(Error::InvalidModuleVersion));
+                    }
+
+                    self.state = State::Description;
+                }
+
+                State::Description =>
+                {
+                    self.description = line_str;
+                    self.state = State::RootOffset;
+                }
+
+                State::RootOffset =>
+                {
+                    match crate::parse_int(line).map_err(|err| located_err(0, line.len(), err))
+                    {
+                        Ok(value) => self.root_offset = value,
+                        Err(err) => return Some(Err(err)),
+                    }
+
+                    self.state = State::EntryCount;
+                }
+
+                State::EntryCount =>
+                {
+                    match crate::parse_int(line).map_err(|err| located_err(0, line.len(), err))
+                    {
+                        Ok(value) => self.entry_count = value,
+                        Err(err) => return Some(Err(err)),
+                    }
+
+                    self.state = State::EntryLength;
+                }
+
+                State::EntryLength =>
+                {
+                    let entry_length = match crate::parse_int(line).map_err(|err| located_err(0, line.len(), err))
+                    {
+                        Ok(value) => value,
+                        Err(err) => return Some(Err(err)),
+                    };
+
+                    self.state = State::EntryNames;
+
+                    return Some(Ok(ModuleItem::Header
+                    {
+                        description: self.description.clone(),
+                        root_offset: self.root_offset,
+                        entry_count: self.entry_count,
+                        entry_length,
+                    }));
+                }
+
+                State::EntryNames =>
+                {
+                    self.state = State::Charset;
+                    return Some(Ok(ModuleItem::EntryNames(get_full_filename(parent_dir, line))));
+                }
+
+                State::Charset =>
+                {
+                    self.state = State::ComponentDescription;
+                    return Some(Ok(ModuleItem::Charset(get_full_filename(parent_dir, line))));
+                }
+
+                State::ComponentDescription =>
+                {
+                    self.component_description = Some(line_str);
+                    self.state = State::ComponentOffset;
+                }
+
+                State::ComponentOffset =>
+                {
+                    match crate::parse_int(line).map_err(|err| located_err(0, line.len(), err))
+                    {
+                        Ok(value) => self.component_offset = value,
+                        Err(err) => return Some(Err(err)),
+                    }
+
+                    self.state = State::ComponentLength;
+                }
+
+                State::ComponentLength =>
+                {
+                    match crate::parse_int(line).map_err(|err| located_err(0, line.len(), err))
+                    {
+                        Ok(value) => self.component_length = value,
+                        Err(err) => return Some(Err(err)),
+                    }
+
+                    self.state = State::ComponentKind;
+                }
+
+                State::ComponentKind =>
+                {
+                    self.component_kind_str = Some(line_str);
+                    self.state = State::ComponentDropboxAndEnd;
+                }
+
+                State::ComponentDropboxAndEnd =>
+                {
+                    self.state = State::ComponentDescription;
+
+                    return Some(Ok(ModuleItem::Component
+                    {
+                        description: self.component_description.take().unwrap(),
+                        offset: self.component_offset,
+                        length: self.component_length,
+                        kind_str: self.component_kind_str.take().unwrap(),
+                        dropbox_path: get_full_filename(parent_dir, line),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::loader::FsLoader;
+    use std::io;
+
+    /// A fresh scratch directory per test, cleaned up on drop, so
+    /// `%include`/cycle tests don't interfere with each other or with the
+    /// `dat/` fixtures used elsewhere.
+    struct ScratchDir(PathBuf);
+
+    impl ScratchDir
+    {
+        fn new(name: &str) -> Self
+        {
+            let dir = std::env::temp_dir().join(format!("nightmare-reader-test-{}", name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            ScratchDir(dir)
+        }
+
+        fn write(&self, name: &str, content: &str) -> PathBuf
+        {
+            let path = self.0.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for ScratchDir
+    {
+        fn drop(&mut self)
+        {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn include_splices_lines_from_the_referenced_file()
+    {
+        let dir = ScratchDir::new("include-splice");
+        dir.write("included.nmm", "Spliced\n0\n1\nHEXA\nNULL\n");
+        let main = dir.write("main.nmm", "1\nMain\n0\n1\n1\nNULL\nNULL\n%include included.nmm\n");
+
+        let mut reader = ModuleReader::new(&FsLoader, &main).unwrap();
+
+        let items: Vec<_> = (&mut reader).map(|item| item.unwrap()).collect();
+
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[3], ModuleItem::Component
+        {
+            description: "Spliced".to_string(),
+            offset: 0,
+            length: 1,
+            kind_str: "HEXA".to_string(),
+            dropbox_path: None,
+        });
+    }
+
+    #[test]
+    fn unset_is_yielded_as_its_own_item()
+    {
+        let dir = ScratchDir::new("unset-item");
+        let main = dir.write("main.nmm", "1\nMain\n0\n1\n1\nNULL\nNULL\n%unset Name\n");
+
+        let mut reader = ModuleReader::new(&FsLoader, &main).unwrap();
+        reader.next().unwrap().unwrap(); // Header
+        reader.next().unwrap().unwrap(); // EntryNames
+        reader.next().unwrap().unwrap(); // Charset
+
+        assert_eq!(reader.next().unwrap().unwrap(), ModuleItem::Unset("Name".to_string()));
+    }
+
+    #[test]
+    fn differently_spelled_include_cycle_is_still_detected()
+    {
+        let dir = ScratchDir::new("include-cycle");
+        dir.write("sub/placeholder.nmm", "");
+        let main = dir.write("main.nmm", "1\nMain\n0\n1\n1\nNULL\nNULL\n%include sub/../main.nmm\n");
+
+        let mut reader = ModuleReader::new(&FsLoader, &main).unwrap();
+
+        // Header, EntryNames, Charset all resolve before the %include is hit.
+        reader.next().unwrap().unwrap();
+        reader.next().unwrap().unwrap();
+        reader.next().unwrap().unwrap();
+
+        assert!(matches!(reader.next(), Some(Err(Error::IncludeCycle { .. }))));
+    }
+
+    /// A loader that fails the test if a dropbox file is ever opened
+    /// through it, used to prove `ModuleReader` doesn't resolve a
+    /// component's dropbox entries itself — it only ever hands back the
+    /// path, leaving resolution to whoever consumes the item.
+    struct ForbidDropboxLoader;
+
+    impl ResourceLoader for ForbidDropboxLoader
+    {
+        fn open(&self, kind: FileKind, path: &Path) -> io::Result<Box<dyn BufRead>>
+        {
+            assert_ne!(kind, FileKind::Dropbox, "dropbox file should not have been opened: {}", path.display());
+            FsLoader.open(kind, path)
+        }
+    }
+
+    #[test]
+    fn module_reader_never_resolves_a_components_dropbox_file()
+    {
+        let dir = ScratchDir::new("lazy-dropbox");
+        dir.write("dropbox.txt", "1\n0 Zero\n");
+        dir.write("main.nmm", "1\nMain\n0\n1\n1\nNULL\nNULL\nName\n0\n1\nTEXT\nNULL\nKind\n0\n1\nNDHU\ndropbox.txt\n");
+        let main = dir.0.join("main.nmm");
+
+        let reader = ModuleReader::new(&ForbidDropboxLoader, &main).unwrap();
+
+        let items: Vec<_> = reader.map(|item| item.unwrap()).collect();
+
+        // The dropbox path is still surfaced to the caller, unresolved.
+        assert!(matches!(
+            &items[4],
+            ModuleItem::Component { description, dropbox_path: Some(_), .. } if description == "Kind"
+        ));
+    }
+
+    #[test]
+    fn a_caller_can_stop_early_without_touching_later_files()
+    {
+        let dir = ScratchDir::new("lazy-stop-early");
+        dir.write("main.nmm", "1\nMain\n0\n1\n1\nNames.txt\nNULL\nName\n0\n1\nTEXT\nNULL\n");
+        let main = dir.0.join("main.nmm");
+
+        // Names.txt is deliberately never created: a caller stopping before
+        // the EntryNames item is resolved should never need it to exist.
+        let mut reader = ModuleReader::new(&FsLoader, &main).unwrap();
+
+        assert!(matches!(reader.next(), Some(Ok(ModuleItem::Header { .. }))));
+    }
+}
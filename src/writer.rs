@@ -0,0 +1,320 @@
+//! Emits a [`Module`] back out as a canonical `.nmm` file (and its
+//! companion entry-name, charset, and dropbox files), so tools can
+//! generate and edit modules programmatically.
+//!
+//! Writing the auxiliary files is content-preserving: a file whose bytes
+//! already match what would be written is left untouched, and a file that
+//! changed on disk since the [`Module`] was loaded (tracked via the
+//! `*_hash` fields) is never silently clobbered.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::loader::FsLoader;
+use crate::{hash_value, read_charset, read_component_dropbox_entries, read_module_entries};
+use crate::{ComponentKind, Error, Module, NumberFormat};
+
+/// Writes `module` to `path`, along with whichever of its entry-names,
+/// charset, and dropbox files it references.
+///
+/// Auxiliary files are always written as siblings of `path`, by file
+/// name, regardless of where they were originally loaded from — so
+/// writing to a different directory (or a different module entirely)
+/// never reaches back out and overwrites someone else's copy.
+pub fn to_file<P>(module: &Module, path: P) -> Result<(), Error>
+    where P: AsRef<Path>
+{
+    let path = path.as_ref();
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    if let Some(entry_names) = &module.entry_names
+    {
+        let out_path = aux_output_path(module.entry_names_path.as_deref(), base, "entry names")?;
+        write_entry_names(&out_path, entry_names, module.entry_names_hash)?;
+    }
+
+    if let Some(charset) = &module.charset
+    {
+        let out_path = aux_output_path(module.charset_path.as_deref(), base, "charset")?;
+        write_charset(&out_path, charset, module.charset_hash)?;
+    }
+
+    for component in &module.components
+    {
+        if let ComponentKind::Dropbox(_, entries) = &component.kind
+        {
+            if entries.is_empty() && component.dropbox_path.is_none()
+            {
+                continue;
+            }
+
+            let description = format!("dropbox entries for component \"{}\"", component.description);
+            let out_path = aux_output_path(component.dropbox_path.as_deref(), base, &description)?;
+            write_dropbox_entries(&out_path, entries, component.dropbox_hash)?;
+        }
+    }
+
+    let mut file = File::create(path)?;
+    to_writer(module, &mut file)
+}
+
+/// Writes the canonical `.nmm` text for `module` to `writer`.
+///
+/// Referenced paths are written by file name only, since
+/// [`to_file`] always places the auxiliary files it writes as
+/// siblings of the `.nmm` file itself.
+pub fn to_writer<W>(module: &Module, writer: &mut W) -> Result<(), Error>
+    where W: Write
+{
+    writeln!(writer, "1")?;
+    writeln!(writer, "{}", module.description)?;
+    writeln!(writer, "{}", module.root_offset)?;
+    writeln!(writer, "{}", module.entry_count)?;
+    writeln!(writer, "{}", module.entry_length)?;
+    writeln!(writer, "{}", aux_file_name(module.entry_names_path.as_deref(), module.entry_names.is_some(), "entry names")?)?;
+    writeln!(writer, "{}", aux_file_name(module.charset_path.as_deref(), module.charset.is_some(), "charset")?)?;
+
+    for component in &module.components
+    {
+        writeln!(writer, "{}", component.description)?;
+        writeln!(writer, "{}", component.offset)?;
+        writeln!(writer, "{}", component.length)?;
+        writeln!(writer, "{}", component_kind_str(&component.kind)?)?;
+
+        let has_dropbox_data = matches!(&component.kind, ComponentKind::Dropbox(_, entries) if !entries.is_empty());
+        let description = format!("dropbox entries for component \"{}\"", component.description);
+        writeln!(writer, "{}", aux_file_name(component.dropbox_path.as_deref(), has_dropbox_data, &description)?)?;
+    }
+
+    Ok(())
+}
+
+/// Resolves where an auxiliary file should be written: as a sibling of
+/// the module file being written, under its original file name. Errors
+/// if there's data to write but no path to take a file name from.
+fn aux_output_path(path: Option<&Path>, base: &Path, description: &str) -> Result<PathBuf, Error>
+{
+    let path = path.ok_or_else(|| Error::MissingAuxiliaryPath { description: description.to_string() })?;
+    Ok(base.join(file_name_of(path)))
+}
+
+/// The `.nmm` text form of an auxiliary file reference: its file name if
+/// one is set, `NULL` if there's genuinely nothing to reference, or an
+/// error if there's data to write but no path to take a file name from.
+fn aux_file_name(path: Option<&Path>, has_data: bool, description: &str) -> Result<String, Error>
+{
+    match path
+    {
+        Some(path) => Ok(file_name_of(path).to_string_lossy().into_owned()),
+        None if has_data => Err(Error::MissingAuxiliaryPath { description: description.to_string() }),
+        None => Ok("NULL".to_string()),
+    }
+}
+
+fn file_name_of(path: &Path) -> &OsStr
+{
+    path.file_name().unwrap_or(path.as_os_str())
+}
+
+fn component_kind_str(kind: &ComponentKind) -> Result<&'static str, Error>
+{
+    Ok(match kind
+    {
+        ComponentKind::Text => "TEXT",
+        ComponentKind::HexArray => "HEXA",
+        ComponentKind::Number(NumberFormat::Hex) => "NEHU",
+        ComponentKind::Number(NumberFormat::Dec) => "NEDU",
+        ComponentKind::Number(NumberFormat::DecSigned) => "NEDS",
+        ComponentKind::Dropbox(NumberFormat::Hex, _) => "NDHU",
+        ComponentKind::Dropbox(NumberFormat::Dec, _) => "NDDU",
+        ComponentKind::Dropbox(NumberFormat::DecSigned, _) => return Err(Error::InvalidComponentKind),
+    })
+}
+
+fn write_entry_names(path: &Path, names: &[String], loaded_hash: Option<u64>) -> Result<(), Error>
+{
+    if path.exists()
+    {
+        let existing = read_module_entries(&FsLoader, Some(path))?.unwrap_or_default();
+
+        if existing == names { return Ok(()); }
+
+        if Some(hash_value(&existing)) != loaded_hash
+        {
+            return Err(Error::AuxiliaryFileModified { path: path.to_path_buf() });
+        }
+    }
+
+    let mut file = File::create(path)?;
+
+    for name in names
+    {
+        writeln!(file, "{}", name)?;
+    }
+
+    Ok(())
+}
+
+fn write_charset(path: &Path, charset: &std::collections::BTreeMap<u8, char>, loaded_hash: Option<u64>) -> Result<(), Error>
+{
+    if path.exists()
+    {
+        let existing = read_charset(&FsLoader, path)?;
+
+        if &existing == charset { return Ok(()); }
+
+        if Some(hash_value(&existing)) != loaded_hash
+        {
+            return Err(Error::AuxiliaryFileModified { path: path.to_path_buf() });
+        }
+    }
+
+    let mut file = File::create(path)?;
+
+    for (byte, ch) in charset
+    {
+        writeln!(file, "{:02X}={}", byte, ch)?;
+    }
+
+    Ok(())
+}
+
+fn write_dropbox_entries(path: &Path, entries: &[(u32, String)], loaded_hash: Option<u64>) -> Result<(), Error>
+{
+    if path.exists()
+    {
+        let existing = read_component_dropbox_entries(&FsLoader, Some(path))?;
+
+        if existing == entries { return Ok(()); }
+
+        if Some(hash_value(&existing)) != loaded_hash
+        {
+            return Err(Error::AuxiliaryFileModified { path: path.to_path_buf() });
+        }
+    }
+
+    let mut file = File::create(path)?;
+
+    writeln!(file, "{}", entries.len())?;
+
+    for (number, label) in entries
+    {
+        writeln!(file, "{} {}", number, label)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    struct ScratchDir(PathBuf);
+
+    impl ScratchDir
+    {
+        fn new(name: &str) -> Self
+        {
+            let dir = std::env::temp_dir().join(format!("nightmare-writer-test-{}", name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            ScratchDir(dir)
+        }
+
+        fn path(&self, name: &str) -> PathBuf
+        {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for ScratchDir
+    {
+        fn drop(&mut self)
+        {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn write_entry_names_skips_rewrite_when_on_disk_content_already_matches()
+    {
+        let dir = ScratchDir::new("entry-names-unchanged");
+        let path = dir.path("Names.txt");
+        std::fs::write(&path, "Alice\nBob\n").unwrap();
+
+        // `loaded_hash` deliberately doesn't match what's on disk: the
+        // equality check must short-circuit before the hash check is ever
+        // consulted, proving the file is left untouched rather than
+        // rewritten byte-for-byte identically.
+        write_entry_names(&path, &["Alice".to_string(), "Bob".to_string()], Some(0)).unwrap();
+    }
+
+    #[test]
+    fn write_entry_names_refuses_to_overwrite_a_file_changed_since_load()
+    {
+        let dir = ScratchDir::new("entry-names-modified");
+        let path = dir.path("Names.txt");
+        std::fs::write(&path, "Alice\nBob\n").unwrap();
+
+        let loaded_hash = hash_value(&vec!["Alice".to_string(), "Carol".to_string()]);
+
+        assert!(matches!(
+            write_entry_names(&path, &["Dave".to_string()], Some(loaded_hash)),
+            Err(Error::AuxiliaryFileModified { .. })
+        ));
+    }
+
+    #[test]
+    fn write_charset_skips_rewrite_when_on_disk_content_already_matches()
+    {
+        let dir = ScratchDir::new("charset-unchanged");
+        let path = dir.path("Charset.txt");
+        std::fs::write(&path, "41=A\n42=B\n").unwrap();
+
+        let charset = std::collections::BTreeMap::from([(0x41, 'A'), (0x42, 'B')]);
+        write_charset(&path, &charset, Some(0)).unwrap();
+    }
+
+    #[test]
+    fn write_charset_refuses_to_overwrite_a_file_changed_since_load()
+    {
+        let dir = ScratchDir::new("charset-modified");
+        let path = dir.path("Charset.txt");
+        std::fs::write(&path, "41=A\n42=B\n").unwrap();
+
+        let loaded_hash = hash_value(&std::collections::BTreeMap::from([(0x41u8, 'A')]));
+
+        assert!(matches!(
+            write_charset(&path, &std::collections::BTreeMap::from([(0x43, 'C')]), Some(loaded_hash)),
+            Err(Error::AuxiliaryFileModified { .. })
+        ));
+    }
+
+    #[test]
+    fn write_dropbox_entries_skips_rewrite_when_on_disk_content_already_matches()
+    {
+        let dir = ScratchDir::new("dropbox-unchanged");
+        let path = dir.path("Dropbox.txt");
+        std::fs::write(&path, "1\n0 Zero\n").unwrap();
+
+        write_dropbox_entries(&path, &[(0, "Zero".to_string())], Some(0)).unwrap();
+    }
+
+    #[test]
+    fn write_dropbox_entries_refuses_to_overwrite_a_file_changed_since_load()
+    {
+        let dir = ScratchDir::new("dropbox-modified");
+        let path = dir.path("Dropbox.txt");
+        std::fs::write(&path, "1\n0 Zero\n").unwrap();
+
+        let loaded_hash = hash_value(&vec![(0u32, "Nil".to_string())]);
+
+        assert!(matches!(
+            write_dropbox_entries(&path, &[(1, "One".to_string())], Some(loaded_hash)),
+            Err(Error::AuxiliaryFileModified { .. })
+        ));
+    }
+}